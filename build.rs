@@ -1,9 +1,9 @@
 use std::{
+    collections::hash_map::DefaultHasher,
+    env,
     ffi::OsString,
-    fs::OpenOptions,
-    io::Write,
+    hash::{Hash, Hasher},
     path::{Path, PathBuf},
-    process::Command,
     sync::LazyLock,
 };
 
@@ -17,12 +17,220 @@ static SOURCE_INCLUDE_PLATFORM_DIR: LazyLock<PathBuf> =
     LazyLock::new(|| SOURCE_INCLUDE_DIR.join("platform/"));
 static SOURCE_COMPONENTS_DIR: LazyLock<PathBuf> = LazyLock::new(|| SOURCE_DIR.join("components/"));
 
+static OUT_DIR: LazyLock<PathBuf> =
+    LazyLock::new(|| PathBuf::from(env::var_os("OUT_DIR").unwrap()));
+
+const ACPICA_LIB_NAME: &str = "acpica";
+
 fn main() {
+    // Cargo only reruns `build.rs` for env vars it's told to watch; without these, switching
+    // between a vendored and a system ACPICA (or moving the build cache) between builds with no
+    // other changes would silently keep re-emitting the stale build's link/cache directives.
+    println!("cargo:rerun-if-env-changed=ACPICA_SYS_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=ACPICA_SYS_INCLUDE_DIR");
+    println!("cargo:rerun-if-env-changed=ACPICA_SYS_CACHE_DIR");
+
+    let system = system_acpica();
+
+    if let Some(system) = &system {
+        link_system_acpica(system);
+    } else {
+        build_vendored_acpica();
+    }
+
+    finish_bindings(system);
+}
+
+// `bindgen` is an optional build-dependency, so everything that touches it — including which
+// branch runs here — has to be gated at compile time, not just behind a runtime `cfg!()` check.
+#[cfg(feature = "bindgen")]
+fn finish_bindings(system: Option<SystemAcpica>) {
+    // Only resolved here, so a cache-hit + prebuilt-bindings build (the common case for a
+    // dependent OS kernel) never forces `TEMP_DIR`'s `mkdtemp` just to compute an unused path.
+    let include_dir = system
+        .map(|system| system.include_dir)
+        .unwrap_or_else(|| SOURCE_INCLUDE_DIR.clone());
+    generate_bindings(&include_dir);
+}
+
+#[cfg(not(feature = "bindgen"))]
+fn finish_bindings(_system: Option<SystemAcpica>) {
+    use_prebuilt_bindings();
+}
+
+struct SystemAcpica {
+    lib_dir: PathBuf,
+    // Only read by `finish_bindings()` when the `bindgen` feature is enabled.
+    #[cfg_attr(not(feature = "bindgen"), allow(dead_code))]
+    include_dir: PathBuf,
+}
+
+/// Reads `ACPICA_SYS_LIB_DIR`/`ACPICA_SYS_INCLUDE_DIR` from the environment. When both are set,
+/// the caller should link against the static `libacpica` found there instead of compiling the
+/// vendored source tree — useful for distro packaging or a custom-built/hardened ACPICA.
+fn system_acpica() -> Option<SystemAcpica> {
+    let lib_dir = env::var_os("ACPICA_SYS_LIB_DIR").map(PathBuf::from);
+    let include_dir = env::var_os("ACPICA_SYS_INCLUDE_DIR").map(PathBuf::from);
+
+    match (lib_dir, include_dir) {
+        (Some(lib_dir), Some(include_dir)) => Some(SystemAcpica {
+            lib_dir,
+            include_dir,
+        }),
+        (None, None) => None,
+        (Some(_), None) => panic!(
+            "`ACPICA_SYS_LIB_DIR` is set but `ACPICA_SYS_INCLUDE_DIR` is not; both must be set to link a system ACPICA"
+        ),
+        (None, Some(_)) => panic!(
+            "`ACPICA_SYS_INCLUDE_DIR` is set but `ACPICA_SYS_LIB_DIR` is not; both must be set to link a system ACPICA"
+        ),
+    }
+}
+
+fn link_system_acpica(system: &SystemAcpica) {
+    println!(
+        "cargo:rustc-link-search=native={}",
+        system.lib_dir.display()
+    );
+    println!("cargo:rustc-link-lib=static={ACPICA_LIB_NAME}");
+}
+
+/// Compiles the vendored ACPICA tree, or reuses a previously-compiled static archive keyed by a
+/// digest of the vendored sources, the selected platform header, and the effective `cc` flags.
+/// On a cache hit this skips `prepare_temp_dir()`/`compile_acpica()` entirely and just re-emits
+/// the link directives, turning a dependent's rebuild into a near-instant link step.
+fn build_vendored_acpica() {
+    let cache_dir = acpica_cache_dir(&acpica_build_digest());
+    let cached_lib = cache_dir.join(format!("lib{ACPICA_LIB_NAME}.a"));
+
+    if cached_lib.exists() {
+        println!("cargo:rustc-link-search=native={}", cache_dir.display());
+        println!("cargo:rustc-link-lib=static={ACPICA_LIB_NAME}");
+
+        // `compile_acpica()` is skipped, but bindgen still needs the prepared, patched headers.
+        if cfg!(feature = "bindgen") {
+            prepare_temp_dir();
+            patch_acrust_include();
+        }
+
+        return;
+    }
+
     prepare_temp_dir();
     patch_acrust_include();
     compile_acpica();
-    generate_bindings();
-    cleanup();
+
+    // Copy to a temp file in the same directory and rename into place, so a build killed
+    // mid-copy (Ctrl-C, OOM, CI timeout) can never leave a truncated archive at `cached_lib`
+    // for a later build's `cached_lib.exists()` check to trust.
+    std::fs::create_dir_all(&cache_dir).expect("failed to create ACPICA build cache directory");
+    let staged_lib = cache_dir.join(format!("lib{ACPICA_LIB_NAME}.a.tmp-{}", std::process::id()));
+    std::fs::copy(OUT_DIR.join(format!("lib{ACPICA_LIB_NAME}.a")), &staged_lib)
+        .expect("failed to stage compiled ACPICA static library for caching");
+    std::fs::rename(&staged_lib, &cached_lib)
+        .expect("failed to move staged ACPICA static library into the cache");
+}
+
+/// Root directory under which compiled ACPICA archives are cached, keyed by digest.
+/// Overridable with `ACPICA_SYS_CACHE_DIR`; otherwise a stable spot under the system temp dir so
+/// it survives across `OUT_DIR`s (which change whenever Cargo's own fingerprint does).
+fn acpica_cache_dir(digest: &str) -> PathBuf {
+    let base = env::var_os("ACPICA_SYS_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("acpica-sys-cache"));
+
+    base.join(digest)
+}
+
+/// Path to the platform header `prepare_temp_dir()` would select for the current target: an
+/// arch-specific variant (e.g. `c_headers/acrust-aarch64.h`) if one exists, else the generic
+/// fallback. Shared so the cache digest and the actual copy never disagree on which file it is.
+fn acrust_header_path() -> PathBuf {
+    let arch_header_path = PathBuf::from(format!("c_headers/acrust-{}.h", target_arch()));
+    if arch_header_path.exists() {
+        arch_header_path
+    } else {
+        PathBuf::from("c_headers/acrust.h")
+    }
+}
+
+/// Hashes the vendored ACPICA source tree, the platform header `prepare_temp_dir()` would select,
+/// the full target triple, and the defines `compile_acpica()` would pass to `cc`, so identical
+/// inputs always resolve to the same cache entry and any change to either invalidates it.
+fn acpica_build_digest() -> String {
+    let mut hasher = DefaultHasher::new();
+
+    hash_dir(&mut hasher, Path::new("acpica/source/"));
+
+    let header_path = acrust_header_path();
+    std::fs::read(&header_path)
+        .unwrap_or_else(|_| panic!("failed to read `{}`", header_path.display()))
+        .hash(&mut hasher);
+
+    // The full triple, not just pointer width/endianness, so e.g. two different 64-bit
+    // little-endian targets (a hosted OS vs. a `-none` freestanding target) never collide.
+    (
+        env::var("TARGET").unwrap_or_default(),
+        cfg!(feature = "debug-output"),
+        cfg!(feature = "aml-debugger"),
+        cfg!(feature = "aml-disassembler"),
+    )
+        .hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Hashes every file under `dir`, recursively, in a deterministic (sorted) order.
+fn hash_dir(hasher: &mut impl Hasher, dir: &Path) {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap_or_else(|_| panic!("failed to read `{}`", dir.display()))
+        .map(|entry| entry.expect("failed to read directory entry").path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            hash_dir(hasher, &path);
+        } else {
+            path.hash(hasher);
+            std::fs::read(&path)
+                .unwrap_or_else(|_| panic!("failed to read `{}`", path.display()))
+                .hash(hasher);
+        }
+    }
+}
+
+fn target_arch() -> String {
+    env::var("CARGO_CFG_TARGET_ARCH").expect("`CARGO_CFG_TARGET_ARCH` not set")
+}
+
+/// Path to the prebuilt bindings file for the target currently being built, e.g.
+/// `src/bindings/x86_64-linux-gnu.rs`, or `src/bindings/x86_64-none-.rs` for a target with an
+/// empty `CARGO_CFG_TARGET_ENV` such as `x86_64-unknown-none`.
+fn target_bindings_path() -> PathBuf {
+    let arch = target_arch();
+    let os = env::var("CARGO_CFG_TARGET_OS").expect("`CARGO_CFG_TARGET_OS` not set");
+    let env = env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+
+    Path::new("src/bindings").join(format!("{arch}-{os}-{env}.rs"))
+}
+
+/// Copies the prebuilt bindings file for the current target into `OUT_DIR`, so `src/lib.rs` can
+/// `include!` it unconditionally regardless of whether `bindgen` generated it this run.
+#[cfg(not(feature = "bindgen"))]
+fn use_prebuilt_bindings() {
+    let bindings_path = target_bindings_path();
+
+    if !bindings_path.exists() {
+        panic!(
+            "no prebuilt bindings for this target at `{}`; either commit one (see the \
+             `update-bindings` feature) or build with the `bindgen` feature enabled",
+            bindings_path.display()
+        );
+    }
+
+    std::fs::copy(bindings_path, OUT_DIR.join("bindings.rs"))
+        .expect("failed to copy prebuilt bindings into `OUT_DIR`");
 }
 
 fn prepare_temp_dir() {
@@ -49,9 +257,9 @@ fn prepare_temp_dir() {
     copy_dir_all("acpica/source/", SOURCE_DIR.as_path())
         .expect("failed to copy ACPICA source files to temporary directory for compilation");
 
-    // copy the custom platform header we've premade
+    // copy the custom platform header we've premade, preferring an arch-specific variant
     std::fs::copy(
-        "c_headers/acrust.h",
+        acrust_header_path(),
         SOURCE_INCLUDE_PLATFORM_DIR.join("acrust.h"),
     )
     .expect("failed to copy `acrust.h` platform headers");
@@ -79,23 +287,51 @@ fn patch_acrust_include() {
 }
 
 fn compile_acpica() {
-    cc::Build::new()
+    let pointer_width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+        .expect("`CARGO_CFG_TARGET_POINTER_WIDTH` not set");
+    let endian = env::var("CARGO_CFG_TARGET_ENDIAN").expect("`CARGO_CFG_TARGET_ENDIAN` not set");
+
+    let mut build = cc::Build::new();
+    build
         .warnings(false)
         .include(SOURCE_INCLUDE_DIR.as_path())
-        .define("ACPI_DEBUG_OUTPUT", None)
+        // ACPICA's `actypes.h` switches pointer-sized typedefs on this define.
+        .define("ACPI_MACHINE_WIDTH", pointer_width.as_str())
         .flag("-fno-stack-protector")
         .flag("-Wno-format-truncation") // Get rid of annoying warning when compiling ACPICA.
-        .opt_level(1)
+        .opt_level(1);
+
+    if endian == "big" {
+        build.define("ACPI_BIG_ENDIAN", None);
+    }
+
+    if cfg!(feature = "debug-output") {
+        build.define("ACPI_DEBUG_OUTPUT", None);
+    }
+
+    // The debugger and disassembler each reach into the other's headers (e.g. the debugger
+    // calls into `AcpiDmDumpTree`), so both component directories need to be on the include
+    // path once either is compiled in, or `cc` fails with "undefined type" errors.
+    if cfg!(feature = "aml-debugger") || cfg!(feature = "aml-disassembler") {
+        build
+            .include(SOURCE_COMPONENTS_DIR.join("debugger"))
+            .include(SOURCE_COMPONENTS_DIR.join("disassembler"));
+    }
+
+    let mut excluded_components = Vec::new();
+    if !cfg!(feature = "aml-debugger") {
+        excluded_components.push(OsString::from("debugger"));
+    }
+    if !cfg!(feature = "aml-disassembler") {
+        excluded_components.push(OsString::from("disassembler"));
+    }
+
+    build
         .files({
             std::fs::read_dir(SOURCE_COMPONENTS_DIR.as_path())
                 .expect("source directory should contain a `components` sub-directory")
                 .map(|component_dir| component_dir.expect("could not read component directory"))
-                .filter(|component_dir| {
-                    // Exclude the debugger and disassembler dirs because they give 'undefined type' errors.
-                    // TODO consider fixing this if the needs arises on the OS side.
-                    ![OsString::from("debugger"), OsString::from("disassembler")]
-                        .contains(&component_dir.file_name())
-                })
+                .filter(|component_dir| !excluded_components.contains(&component_dir.file_name()))
                 .flat_map(|component_dir| {
                     std::fs::read_dir(component_dir.path())
                         .expect("failed to read the files within the component directory")
@@ -105,49 +341,109 @@ fn compile_acpica() {
                         .map(|c_file| c_file.path())
                 })
         })
-        .compile("acpica");
+        .compile(ACPICA_LIB_NAME);
 }
 
-fn generate_bindings() {
+/// ACPICA exposes `AE_*` status codes and `ACPI_*` type-group constants as `#define` constants
+/// over a plain integer typedef (e.g. `typedef UINT32 ACPI_STATUS;`), not as C `enum`s — so
+/// bindgen's `rustified_enum`/`bitfield_enum` builder methods, which only retype actual `enum`
+/// AST nodes, can't apply to them. Tag the constants' type via `int_macro` instead, so they come
+/// out typed as the ACPICA typedef they belong to rather than a generic `u32`.
+///
+/// The `*_FLAGS` bitflag groups have the same plain-`#define` problem, but unlike the groups
+/// below, each one is a separate per-use typedef (init flags, GPE flags, ...) without a shared
+/// constant-name prefix to key off of here — mapping them needs the actual prefix-to-typedef
+/// table read out of the vendored headers, so they're left untyped until that's available.
+#[cfg(feature = "bindgen")]
+#[derive(Debug)]
+struct AcpicaTypedConstants;
+
+#[cfg(feature = "bindgen")]
+impl bindgen::callbacks::ParseCallbacks for AcpicaTypedConstants {
+    fn int_macro(&self, name: &str, _value: i64) -> Option<bindgen::callbacks::IntKind> {
+        let type_name = if name.starts_with("AE_") {
+            "ACPI_STATUS"
+        } else if name.starts_with("ACPI_TYPE_") {
+            "ACPI_OBJECT_TYPE"
+        } else if name.starts_with("ACPI_ADR_SPACE_") {
+            "ACPI_ADR_SPACE_TYPE"
+        } else {
+            return None;
+        };
+
+        Some(bindgen::callbacks::IntKind::Custom {
+            name: type_name,
+            is_signed: false,
+        })
+    }
+}
+
+/// Generates bindings into `OUT_DIR/bindings.rs`, for `src/lib.rs` to `include!`. If the
+/// `update-bindings` feature is enabled, also copies the freshly generated bindings back into
+/// `src/bindings/` so maintainers can review and commit the update for this target.
+#[cfg(feature = "bindgen")]
+fn generate_bindings(include_dir: &Path) {
+    use std::process::Command;
+
+    // Thread the same target triple `compile_acpica()` built for into clang, so generated type
+    // layouts (pointer width, endianness) match what was actually compiled.
+    let target = env::var("TARGET").expect("`TARGET` not set");
+
+    let header_path = include_dir.join("acpi.h");
     let bindings = bindgen::Builder::default()
         .use_core()
-        .header("acpica/source/include/acpi.h")
+        .header(
+            header_path
+                .to_str()
+                .expect("include dir path should be valid UTF-8"),
+        )
+        .clang_arg(format!("-I{}", include_dir.display()))
+        .clang_arg(format!("--target={target}"))
+        // Keep the binding surface to what ACPICA actually exposes, rather than every
+        // transitively-included libc type.
+        .allowlist_function("Acpi.*")
+        .allowlist_type("ACPI_.*")
+        .allowlist_var("(ACPI|AE)_.*")
+        // ACPICA's C integer typedefs (`UINT8`, `INT32`, `BOOLEAN`, ...) just alias the
+        // equivalent `core` primitives; block bindgen's translation of them and define each
+        // directly as that primitive. A blocklisted type is still referenced by name in the
+        // generated items, so these definitions have to be emitted alongside them.
+        .blocklist_type("U?INT(8|16|32|64)|BOOLEAN|COMPILER_DEPENDENT_.*")
+        .raw_line("pub type BOOLEAN = u8;")
+        .raw_line("pub type UINT8 = u8;")
+        .raw_line("pub type INT8 = i8;")
+        .raw_line("pub type UINT16 = u16;")
+        .raw_line("pub type INT16 = i16;")
+        .raw_line("pub type UINT32 = u32;")
+        .raw_line("pub type INT32 = i32;")
+        .raw_line("pub type UINT64 = u64;")
+        .raw_line("pub type INT64 = i64;")
+        .raw_line("pub type COMPILER_DEPENDENT_UINT64 = u64;")
+        .raw_line("pub type COMPILER_DEPENDENT_INT64 = i64;")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
+        .parse_callbacks(Box::new(AcpicaTypedConstants))
         .generate()
         .expect("failed to generate bindings");
 
-    let mut lib_file = OpenOptions::new()
-        .create(false)
-        .write(true)
-        .truncate(true)
-        .open("src/lib.rs")
-        .expect("could not open `lib.rs`");
-    lib_file
-        .write_fmt(format_args!(
-            r#"#![no_std]
-#![allow(
-    dead_code,
-    unused_imports,
-    improper_ctypes,
-    non_snake_case,
-    non_camel_case_types,
-    non_upper_case_globals,
-    unsafe_op_in_unsafe_fn,
-    clippy::missing_safety_doc
-)]
-
-"#
-        ))
-        .expect("failed to write attributes to `lib.rs`");
-
+    let out_path = OUT_DIR.join("bindings.rs");
     bindings
-        .write(Box::new(&lib_file))
-        .expect("failed to write bindings");
-}
+        .write_to_file(&out_path)
+        .expect("failed to write generated bindings to `OUT_DIR`");
 
-fn cleanup() {
-    Command::new("cargo")
-        .arg("fmt")
-        .output()
-        .expect("failed to format crate");
+    if cfg!(feature = "update-bindings") {
+        let committed_path = target_bindings_path();
+        std::fs::create_dir_all(
+            committed_path
+                .parent()
+                .expect("bindings path should have a parent directory"),
+        )
+        .expect("failed to create `src/bindings` directory");
+        std::fs::copy(&out_path, &committed_path)
+            .expect("failed to copy generated bindings into `src/bindings`");
+
+        Command::new("rustfmt")
+            .arg(&committed_path)
+            .output()
+            .expect("failed to format updated bindings");
+    }
 }
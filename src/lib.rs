@@ -0,0 +1,15 @@
+#![no_std]
+#![allow(
+    dead_code,
+    unused_imports,
+    improper_ctypes,
+    non_snake_case,
+    non_camel_case_types,
+    non_upper_case_globals,
+    unsafe_op_in_unsafe_fn,
+    clippy::missing_safety_doc
+)]
+
+// Populated by `build.rs`: either bindgen-generated (the `bindgen` feature) or copied from a
+// prebuilt `src/bindings/{arch}-{os}-{env}.rs` file for the target being built.
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));